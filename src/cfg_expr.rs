@@ -0,0 +1,264 @@
+use std::fmt;
+
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, ToTokens};
+use syn::parse::{Parse, ParseStream};
+use syn::{LitStr, Token};
+
+/// A single leaf predicate inside a `cfg()` expression: either a bare name
+/// (`unix`, `windows`) or a key/value pair (`target_os = "linux"`).
+#[derive(Clone, Debug)]
+pub enum Cfg {
+    Name(String),
+    KeyPair(String, String),
+}
+
+/// Recursive `cfg()` predicate grammar, mirroring what rustc/Cargo accept
+/// inside `#[cfg(...)]`: `all(...)`, `any(...)`, `not(...)`, and leaf
+/// predicates.
+#[derive(Clone, Debug)]
+pub enum CfgExpr {
+    Not(Box<CfgExpr>),
+    All(Vec<CfgExpr>),
+    Any(Vec<CfgExpr>),
+    Value(Cfg),
+}
+
+impl CfgExpr {
+    /// Cosmetic flattening of every OS name referenced anywhere in the
+    /// expression (bare names and `target_os = "..."` values alike),
+    /// ignoring `not()` polarity. This is **not** sound for coverage
+    /// reasoning — `all(unix, not(target_os = "macos"))` flattens to
+    /// `["unix", "macos"]` even though the expression *excludes* macOS.
+    /// `os_impl` records this list in each `os_impl_*` registry const; use
+    /// [`CfgExpr::target_os_disjunction`] wherever coverage/support
+    /// actually needs to be reasoned about.
+    pub fn cosmetic_os_names(&self) -> Vec<String> {
+        let mut out = Vec::new();
+        self.collect_cosmetic_os_names(&mut out);
+        out
+    }
+
+    fn collect_cosmetic_os_names(&self, out: &mut Vec<String>) {
+        match self {
+            CfgExpr::Value(Cfg::Name(name)) => out.push(name.clone()),
+            CfgExpr::Value(Cfg::KeyPair(key, value)) if key == "target_os" => {
+                out.push(value.clone())
+            }
+            CfgExpr::Value(Cfg::KeyPair(..)) => {}
+            CfgExpr::Not(inner) => inner.collect_cosmetic_os_names(out),
+            CfgExpr::All(list) | CfgExpr::Any(list) => {
+                for expr in list {
+                    expr.collect_cosmetic_os_names(out);
+                }
+            }
+        }
+    }
+
+    /// Soundly reduces the expression to the set of `target_os` values it is
+    /// *exactly* equivalent to, or `None` if it can't be soundly reduced to
+    /// a flat OS list. Only a single `target_os = "..."` leaf, or an `any()`
+    /// built entirely of such leaves (possibly nested), reduces — `not()`,
+    /// `all()`, bare names, and any other `key = "value"` pair make the
+    /// expression's real coverage depend on more than just `target_os`, so
+    /// they deliberately return `None` rather than guess.
+    pub fn target_os_disjunction(&self) -> Option<Vec<String>> {
+        match self {
+            CfgExpr::Value(Cfg::KeyPair(key, value)) if key == "target_os" => {
+                Some(vec![value.clone()])
+            }
+            CfgExpr::Any(list) => {
+                let mut out = Vec::new();
+                for expr in list {
+                    out.extend(expr.target_os_disjunction()?);
+                }
+                Some(out)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Parse for CfgExpr {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let ident: syn::Ident = input.parse()?;
+        let name = ident.to_string();
+
+        if input.peek(syn::token::Paren) {
+            let content;
+            syn::parenthesized!(content in input);
+            match name.as_str() {
+                "not" => {
+                    let inner: CfgExpr = content.parse()?;
+                    Ok(CfgExpr::Not(Box::new(inner)))
+                }
+                "all" => {
+                    let list =
+                        content.parse_terminated(CfgExpr::parse, Token![,])?;
+                    Ok(CfgExpr::All(list.into_iter().collect()))
+                }
+                "any" => {
+                    let list =
+                        content.parse_terminated(CfgExpr::parse, Token![,])?;
+                    Ok(CfgExpr::Any(list.into_iter().collect()))
+                }
+                other => Err(syn::Error::new(
+                    ident.span(),
+                    format!(
+                        "unknown cfg predicate `{}`, expected `all`, `any`, or `not`",
+                        other
+                    ),
+                )),
+            }
+        } else if input.peek(Token![=]) {
+            input.parse::<Token![=]>()?;
+            let value: LitStr = input.parse()?;
+            Ok(CfgExpr::Value(Cfg::KeyPair(name, value.value())))
+        } else {
+            Ok(CfgExpr::Value(Cfg::Name(name)))
+        }
+    }
+}
+
+impl ToTokens for Cfg {
+    fn to_tokens(&self, tokens: &mut TokenStream2) {
+        match self {
+            Cfg::Name(name) => {
+                let ident = syn::Ident::new(name, proc_macro2::Span::call_site());
+                tokens.extend(quote! { #ident });
+            }
+            Cfg::KeyPair(key, value) => {
+                let ident = syn::Ident::new(key, proc_macro2::Span::call_site());
+                let lit = LitStr::new(value, proc_macro2::Span::call_site());
+                tokens.extend(quote! { #ident = #lit });
+            }
+        }
+    }
+}
+
+impl ToTokens for CfgExpr {
+    fn to_tokens(&self, tokens: &mut TokenStream2) {
+        match self {
+            CfgExpr::Value(cfg) => cfg.to_tokens(tokens),
+            CfgExpr::Not(inner) => tokens.extend(quote! { not(#inner) }),
+            CfgExpr::All(list) => tokens.extend(quote! { all(#(#list),*) }),
+            CfgExpr::Any(list) => tokens.extend(quote! { any(#(#list),*) }),
+        }
+    }
+}
+
+impl fmt::Display for Cfg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cfg::Name(name) => write!(f, "{}", name),
+            Cfg::KeyPair(key, value) => write!(f, "{} = \"{}\"", key, value),
+        }
+    }
+}
+
+impl fmt::Display for CfgExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CfgExpr::Value(cfg) => write!(f, "{}", cfg),
+            CfgExpr::Not(inner) => write!(f, "not({})", inner),
+            CfgExpr::All(list) => {
+                write!(f, "all(")?;
+                for (i, expr) in list.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", expr)?;
+                }
+                write!(f, ")")
+            }
+            CfgExpr::Any(list) => {
+                write!(f, "any(")?;
+                for (i, expr) in list.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", expr)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(src: &str) -> CfgExpr {
+        syn::parse_str::<CfgExpr>(src).expect("valid cfg() expression")
+    }
+
+    #[test]
+    fn parses_and_round_trips_a_bare_name() {
+        let expr = parse("unix");
+        assert_eq!(expr.to_string(), "unix");
+    }
+
+    #[test]
+    fn parses_and_round_trips_a_key_pair() {
+        let expr = parse(r#"target_os = "linux""#);
+        assert_eq!(expr.to_string(), r#"target_os = "linux""#);
+    }
+
+    #[test]
+    fn parses_and_round_trips_nested_all_any_not() {
+        let expr = parse(r#"all(unix, not(target_os = "macos"))"#);
+        assert_eq!(expr.to_string(), r#"all(unix, not(target_os = "macos"))"#);
+
+        let expr = parse(r#"any(target_arch = "x86_64", target_env = "musl")"#);
+        assert_eq!(
+            expr.to_string(),
+            r#"any(target_arch = "x86_64", target_env = "musl")"#
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_predicate() {
+        assert!(syn::parse_str::<CfgExpr>("whatever(unix)").is_err());
+    }
+
+    #[test]
+    fn target_os_disjunction_reduces_pure_any_of_target_os() {
+        let expr = parse(r#"any(target_os = "linux", target_os = "windows")"#);
+        assert_eq!(
+            expr.target_os_disjunction(),
+            Some(vec!["linux".to_string(), "windows".to_string()])
+        );
+
+        let expr = parse(r#"target_os = "macos""#);
+        assert_eq!(expr.target_os_disjunction(), Some(vec!["macos".to_string()]));
+    }
+
+    #[test]
+    fn target_os_disjunction_refuses_not_and_all() {
+        // `all(unix, not(target_os = "macos"))` excludes macOS — it must not
+        // reduce to `["unix", "macos"]` the way the cosmetic flattening does.
+        let expr = parse(r#"all(unix, not(target_os = "macos"))"#);
+        assert_eq!(expr.target_os_disjunction(), None);
+        assert_eq!(
+            expr.cosmetic_os_names(),
+            vec!["unix".to_string(), "macos".to_string()]
+        );
+
+        let expr = parse(r#"not(target_os = "macos")"#);
+        assert_eq!(expr.target_os_disjunction(), None);
+    }
+
+    #[test]
+    fn target_os_disjunction_refuses_non_target_os_keys_and_bare_names() {
+        assert_eq!(parse("unix").target_os_disjunction(), None);
+        assert_eq!(
+            parse(r#"target_arch = "x86_64""#).target_os_disjunction(),
+            None
+        );
+        assert_eq!(
+            parse(r#"any(target_os = "linux", unix)"#).target_os_disjunction(),
+            None
+        );
+    }
+}
@@ -1,44 +1,96 @@
+use std::hash::{Hash, Hasher};
+
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{parse_macro_input, ItemImpl, ItemStruct, LitStr, Type};
+use syn::{parse_macro_input, ItemImpl, ItemStruct, ItemTrait, LitStr, Token, TraitItem, Type};
 use syn::parse::{Parse, ParseStream};
 
+mod cfg_expr;
+mod verify;
+
+use cfg_expr::{Cfg, CfgExpr};
+
+/// See [`verify::verify_os_coverage`] for the implementation; proc-macro
+/// entry points must live at the crate root.
+#[proc_macro]
+pub fn verify_os_coverage(input: TokenStream) -> TokenStream {
+    verify::verify_os_coverage(input)
+}
+
 /// Attribute to mark an OS-specific implementation for a trait.
-/// Accepts a comma-separated list of OSes (e.g., "windows, linux").
+///
+/// Accepts the legacy comma-separated list of OSes (e.g. `"windows, linux"`,
+/// sugar for `any(target_os = "windows", target_os = "linux")`), the full
+/// `cfg()` predicate grammar (e.g. `all(unix, not(target_os = "macos"))` or
+/// `any(target_arch = "x86_64", target_env = "musl")`), or `default` /
+/// `fallback` to mark this impl as the catch-all for every target not
+/// covered by one of the trait's other `os_impl` blocks. The default's
+/// `cfg` is `not(any(...))` over the conditions of every non-default
+/// `os_impl` for the same trait found in the crate's `src` tree.
 #[proc_macro_attribute]
 pub fn os_impl(args: TokenStream, input: TokenStream) -> TokenStream {
     let args = parse_macro_input!(args as OsArg);
-    let os_list = args.os_list.value();
-    let oses: Vec<String> = os_list.split(',').map(|s| s.trim().to_string()).collect();
     let impl_block = parse_macro_input!(input as ItemImpl);
 
     let trait_name = impl_block.trait_.as_ref().unwrap().1.clone();
     let _struct_name = impl_block.self_ty.clone();
     let trait_ident = trait_name.segments.last().unwrap().ident.clone();
 
-    // Generate a unique identifier for this impl group
+    let is_default = matches!(args, OsArg::Default);
+    let expr = match args {
+        OsArg::Expr(expr) => expr,
+        OsArg::Default => {
+            let (siblings, default_count) = verify::sibling_conditions_for_trait(&trait_ident.to_string());
+            if default_count > 1 {
+                panic!(
+                    "at most one #[os_impl(default)] is allowed per trait (trait `{}` has {})",
+                    trait_ident, default_count
+                );
+            }
+            // A default covers whatever none of its concrete siblings do.
+            CfgExpr::Not(Box::new(CfgExpr::Any(siblings)))
+        }
+    };
+    let oses = expr.cosmetic_os_names();
+    let expr_str = expr.to_string();
+
+    // Generate a unique identifier for this impl group. `oses` can't be used
+    // for uniqueness here: two os_impl blocks on the same trait with
+    // different non-target_os predicates (e.g. two target_arch-only cfgs)
+    // cosmetically flatten to the same (possibly empty) OS list, colliding
+    // on the same const name. Hash the exact cfg text instead.
     let impl_ident = syn::Ident::new(
-        &format!("os_impl_{}_{}", trait_ident, oses.join("_")),
+        &if is_default {
+            format!("os_impl_{}_default", trait_ident)
+        } else {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            expr_str.hash(&mut hasher);
+            format!("os_impl_{}_{:x}", trait_ident, hasher.finish())
+        },
         proc_macro2::Span::call_site(),
     );
 
-    // Generate cfg condition for all OSes in the group
-    let os_conditions = oses.iter().map(|os| {
-        let os_lit = syn::LitStr::new(os, proc_macro2::Span::call_site());
-        quote! { target_os = #os_lit }
-    });
-
     let output = quote! {
         #[allow(non_upper_case_globals)]
-        pub const #impl_ident: (&str, &[&str]) = (stringify!(#trait_ident), &[#(#oses),*]);
-        #[cfg(any(#(#os_conditions),*))]
+        pub const #impl_ident: (&str, &[&str], &str) =
+            (stringify!(#trait_ident), &[#(#oses),*], #expr_str);
+        #[cfg(#expr)]
         #impl_block
     };
     output.into()
 }
 
 /// Attribute to enforce OS-specific implementations for a trait on a struct.
-/// Syntax: #[enforce_os_support(TraitName("os1, os2, ..."))]
+///
+/// Syntax: `#[enforce_os_support(TraitName("os1, os2, ..."))]` to reference
+/// the trait by path (the negative impl is then left empty, relying on
+/// rustc's own "not all trait items implemented" error), or
+/// `#[enforce_os_support(trait TraitName { fn foo(&self) -> String; ... } ("os1, os2, ..."))]`
+/// to hand the macro the trait's own definition, in which case the negative
+/// impl gets a stub for every method and associated const with a
+/// `compile_error!` naming the unsupported OS instead of rustc's generic
+/// message (associated types stub to `()`, since `compile_error!` can't
+/// appear in type position).
 /// Can be applied multiple times for different traits.
 #[proc_macro_attribute]
 pub fn enforce_os_support(_args: TokenStream, input: TokenStream) -> TokenStream {
@@ -48,13 +100,26 @@ pub fn enforce_os_support(_args: TokenStream, input: TokenStream) -> TokenStream
     let mut enforce_outputs = Vec::new();
     for attr in struct_item.attrs.iter().filter(|attr| attr.path().is_ident("enforce_os_support")) {
         let args = attr.parse_args::<EnforceArgs>().unwrap();
-        let trait_name = args.trait_ty;
         let os_list = args.os_list.value();
         let required_oses: Vec<String> = os_list.split(',').map(|s| s.trim().to_string()).collect();
 
-        let trait_ident = match &trait_name {
-            Type::Path(type_path) => type_path.path.segments.last().unwrap().ident.clone(),
-            _ => panic!("Trait must be a path type"),
+        let trait_ident = match &args.trait_source {
+            TraitSource::Path(trait_ty) => match trait_ty {
+                Type::Path(type_path) => type_path.path.segments.last().unwrap().ident.clone(),
+                _ => panic!("Trait must be a path type"),
+            },
+            TraitSource::Definition(item_trait) => item_trait.ident.clone(),
+        };
+        // For `TraitSource::Path` the negative impl must name the *full*
+        // path, or an unsupported target fails to resolve the trait instead
+        // of producing the "not all items implemented" error callers rely
+        // on. The inline `Definition` case has no path to preserve — the
+        // definition tokens carry only the trait's bare ident. The macro
+        // does not emit the trait item itself, so the trait must already be
+        // in scope under that ident.
+        let trait_name = match &args.trait_source {
+            TraitSource::Path(trait_ty) => quote! { #trait_ty },
+            TraitSource::Definition(_) => quote! { #trait_ident },
         };
 
         let registry_path = quote! { include!(concat!(env!("OUT_DIR"), "/os_registry.rs")); };
@@ -85,13 +150,53 @@ pub fn enforce_os_support(_args: TokenStream, input: TokenStream) -> TokenStream
             };
         };
 
+        // With the trait's own definition in hand, stub every item so the
+        // negative impl compiles for any trait shape instead of assuming a
+        // single hardcoded method exists. Methods and consts get the real
+        // compile_error! diagnostic; compile_error! isn't valid in type
+        // position, so an associated type falls back to `()` and relies on
+        // a stubbed method or const elsewhere in the trait to surface the
+        // message (a trait made up solely of associated types gets no
+        // compile_error! message: the stub impl compiles, or fails only
+        // where `()` does not meet an associated type's bounds).
+        let stub_methods: Vec<_> = match &args.trait_source {
+            TraitSource::Definition(item_trait) => item_trait
+                .items
+                .iter()
+                .filter_map(|item| match item {
+                    TraitItem::Fn(method) => {
+                        let sig = &method.sig;
+                        Some(quote! {
+                            #sig {
+                                compile_error!(#error_msg);
+                                unreachable!()
+                            }
+                        })
+                    }
+                    TraitItem::Const(constant) => {
+                        let ident = &constant.ident;
+                        let ty = &constant.ty;
+                        Some(quote! {
+                            const #ident: #ty = {
+                                compile_error!(#error_msg);
+                                unreachable!()
+                            };
+                        })
+                    }
+                    TraitItem::Type(assoc_type) => {
+                        let ident = &assoc_type.ident;
+                        Some(quote! { type #ident = (); })
+                    }
+                    _ => None,
+                })
+                .collect(),
+            TraitSource::Path(_) => Vec::new(),
+        };
+
         let enforce_block = quote! {
             #[cfg(not(any(#(#os_conditions),*)))]
             impl #trait_name for #struct_name {
-                fn do_something(&self) -> String {
-                    compile_error!(#error_msg);
-                    unreachable!()
-                }
+                #(#stub_methods)*
             }
         };
 
@@ -111,29 +216,100 @@ pub fn enforce_os_support(_args: TokenStream, input: TokenStream) -> TokenStream
     output.into()
 }
 
+/// Parses the tokens of an `#[os_impl(...)]` attribute. Used by
+/// `verify_os_coverage!()` when re-parsing source files directly.
+pub(crate) fn parse_os_impl_attr(attr: &syn::Attribute) -> OsArg {
+    attr.parse_args::<OsArg>().expect("invalid os_impl(...) attribute")
+}
+
+/// Parses the tokens of an `#[enforce_os_support(...)]` attribute into
+/// `(trait name, required OSes)`. Used by `verify_os_coverage!()` when
+/// re-parsing source files directly.
+pub(crate) fn enforce_os_support_requirement(attr: &syn::Attribute) -> (String, Vec<String>) {
+    let args = attr
+        .parse_args::<EnforceArgs>()
+        .expect("invalid enforce_os_support(...) attribute");
+    let trait_ident = match &args.trait_source {
+        TraitSource::Path(trait_ty) => match trait_ty {
+            Type::Path(type_path) => type_path.path.segments.last().unwrap().ident.to_string(),
+            _ => panic!("Trait must be a path type"),
+        },
+        TraitSource::Definition(item_trait) => item_trait.ident.to_string(),
+    };
+    let required_oses = args
+        .os_list
+        .value()
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .collect();
+    (trait_ident, required_oses)
+}
+
 // Helper structs for parsing attribute arguments
-struct OsArg {
-    os_list: LitStr,
+
+/// A parsed `os_impl` argument: either a concrete `cfg()` predicate, or the
+/// `default`/`fallback` marker for the trait's catch-all impl.
+pub(crate) enum OsArg {
+    Expr(CfgExpr),
+    Default,
 }
 
 impl Parse for OsArg {
     fn parse(input: ParseStream) -> syn::Result<Self> {
-        let os_list = input.parse::<LitStr>()?;
-        Ok(OsArg { os_list })
+        // `default`/`fallback` as the sole argument marks the catch-all impl.
+        let fork = input.fork();
+        if let Ok(ident) = fork.parse::<syn::Ident>() {
+            if fork.is_empty() && (ident == "default" || ident == "fallback") {
+                input.parse::<syn::Ident>()?;
+                return Ok(OsArg::Default);
+            }
+        }
+
+        // Legacy sugar: a bare string literal is a comma-separated OS list,
+        // equivalent to `any(target_os = "...", ...)`.
+        if input.peek(LitStr) {
+            let os_list = input.parse::<LitStr>()?;
+            let oses: Vec<String> = os_list
+                .value()
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .collect();
+            let expr = CfgExpr::Any(
+                oses.into_iter()
+                    .map(|os| CfgExpr::Value(Cfg::KeyPair("target_os".to_string(), os)))
+                    .collect(),
+            );
+            return Ok(OsArg::Expr(expr));
+        }
+
+        let expr = input.parse::<CfgExpr>()?;
+        Ok(OsArg::Expr(expr))
     }
 }
 
+/// Where `enforce_os_support` gets the trait's shape from: either just a
+/// path to it (no method stubs possible) or its full definition tokens
+/// (enough to stub every required method).
+enum TraitSource {
+    Path(Type),
+    Definition(ItemTrait),
+}
+
 struct EnforceArgs {
-    trait_ty: Type,
+    trait_source: TraitSource,
     os_list: LitStr,
 }
 
 impl Parse for EnforceArgs {
     fn parse(input: ParseStream) -> syn::Result<Self> {
-        let trait_ty = input.parse::<Type>()?;
+        let trait_source = if input.peek(Token![trait]) {
+            TraitSource::Definition(input.parse::<ItemTrait>()?)
+        } else {
+            TraitSource::Path(input.parse::<Type>()?)
+        };
         let content;
         syn::parenthesized!(content in input);
         let os_list = content.parse::<LitStr>()?;
-        Ok(EnforceArgs { trait_ty, os_list })
+        Ok(EnforceArgs { trait_source, os_list })
     }
 }
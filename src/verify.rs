@@ -0,0 +1,547 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{Item, LitStr};
+
+use crate::cfg_expr::CfgExpr;
+use crate::OsArg;
+
+/// One `#[os_impl(...)]` block found while walking the source tree.
+struct ImplRecord {
+    trait_name: String,
+    /// `Some(oses)` when `expr` is a pure `target_os` disjunction (see
+    /// [`CfgExpr::target_os_disjunction`]) and coverage can therefore be
+    /// verified soundly; `None` when the expression involves `not()`,
+    /// `all()`, bare names, or non-`target_os` keys, meaning we can't tell
+    /// which concrete OSes it actually covers without evaluating it against
+    /// a real target. Always `None` for a `default`/`fallback` block — its
+    /// real condition is the negation of every sibling and isn't known
+    /// until all siblings have been collected.
+    os_disjunction: Option<Vec<String>>,
+    expr: CfgExpr,
+    is_default: bool,
+    file: String,
+    line: usize,
+}
+
+/// One `#[enforce_os_support(...)]` requirement found on a struct.
+struct RequireRecord {
+    trait_name: String,
+    required_oses: Vec<String>,
+    file: String,
+    line: usize,
+}
+
+/// Finds the 1-based line of the next occurrence of `needle` at or after
+/// `*cursor` (a byte offset into `source`), then advances `*cursor` past it.
+///
+/// `syn::parse_file` is run inside the live proc-macro, so its tokens carry
+/// real compiler spans — and on stable, a real `Span::start()` can't resolve
+/// to an actual source position; it degenerates to the `verify_os_coverage!()`
+/// call site for every single span, making every reported line identical and
+/// wrong. Source files are walked top to bottom in the same order attributes
+/// appear in them, so a forward-only textual search for each attribute's
+/// marker, advancing past each match as it's consumed, recovers the real line
+/// without needing span resolution at all.
+fn find_line(source: &str, cursor: &mut usize, needle: &str) -> usize {
+    let found = source[*cursor..]
+        .find(needle)
+        .map(|offset| *cursor + offset)
+        .unwrap_or(*cursor);
+    *cursor = found + needle.len();
+    source[..found].bytes().filter(|&b| b == b'\n').count() + 1
+}
+
+fn walk_items(
+    items: &[Item],
+    file: &str,
+    source: &str,
+    cursor: &mut usize,
+    impls: &mut Vec<ImplRecord>,
+    requirements: &mut Vec<RequireRecord>,
+) {
+    for item in items {
+        match item {
+            Item::Impl(item_impl) => {
+                for attr in &item_impl.attrs {
+                    if !attr.path().is_ident("os_impl") {
+                        continue;
+                    }
+                    let Some((_, trait_path, _)) = item_impl.trait_.clone() else {
+                        continue;
+                    };
+                    let trait_name = trait_path.segments.last().unwrap().ident.to_string();
+                    let line = find_line(source, cursor, "#[os_impl");
+                    match crate::parse_os_impl_attr(attr) {
+                        OsArg::Expr(expr) => impls.push(ImplRecord {
+                            trait_name,
+                            os_disjunction: expr.target_os_disjunction(),
+                            expr,
+                            is_default: false,
+                            file: file.to_string(),
+                            line,
+                        }),
+                        OsArg::Default => impls.push(ImplRecord {
+                            trait_name,
+                            os_disjunction: None,
+                            expr: CfgExpr::Any(Vec::new()),
+                            is_default: true,
+                            file: file.to_string(),
+                            line,
+                        }),
+                    }
+                }
+            }
+            Item::Struct(item_struct) => {
+                for attr in &item_struct.attrs {
+                    if !attr.path().is_ident("enforce_os_support") {
+                        continue;
+                    }
+                    let (trait_name, required_oses) = crate::enforce_os_support_requirement(attr);
+                    requirements.push(RequireRecord {
+                        trait_name,
+                        required_oses,
+                        file: file.to_string(),
+                        line: find_line(source, cursor, "#[enforce_os_support"),
+                    });
+                }
+            }
+            Item::Mod(item_mod) => {
+                if let Some((_, inner_items)) = &item_mod.content {
+                    walk_items(inner_items, file, source, cursor, impls, requirements);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn walk_dir(dir: &Path, impls: &mut Vec<ImplRecord>, requirements: &mut Vec<RequireRecord>, manifest_dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut paths: Vec<PathBuf> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+    paths.sort();
+    for path in paths {
+        if path.is_dir() {
+            walk_dir(&path, impls, requirements, manifest_dir);
+        } else if path.extension().map(|ext| ext == "rs").unwrap_or(false) {
+            let Ok(source) = fs::read_to_string(&path) else {
+                continue;
+            };
+            let Ok(parsed) = syn::parse_file(&source) else {
+                continue;
+            };
+            let display_path = path
+                .strip_prefix(manifest_dir)
+                .unwrap_or(&path)
+                .display()
+                .to_string();
+            let mut cursor = 0usize;
+            walk_items(&parsed.items, &display_path, &source, &mut cursor, impls, requirements);
+        }
+    }
+}
+
+/// Collects the `CfgExpr` of every non-default `#[os_impl(...)]` registered
+/// for `trait_name` anywhere in the crate's `src` tree, plus how many
+/// `#[os_impl(default)]`/`#[os_impl(fallback)]` blocks exist for it
+/// (including the one currently being expanded, so a lone default sees a
+/// count of 1 and a genuine duplicate sees 2+). Used by `os_impl`'s own
+/// `default` mode to compute the fallback's `cfg` as the negation of every
+/// concrete sibling condition.
+pub(crate) fn sibling_conditions_for_trait(trait_name: &str) -> (Vec<CfgExpr>, usize) {
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR")
+        .expect("#[os_impl(default)] must be expanded while building a crate (CARGO_MANIFEST_DIR unset)");
+    let manifest_dir = PathBuf::from(manifest_dir);
+    let src_dir = manifest_dir.join("src");
+
+    let mut impls = Vec::new();
+    let mut requirements = Vec::new();
+    walk_dir(&src_dir, &mut impls, &mut requirements, &manifest_dir);
+
+    let mut concrete = Vec::new();
+    let mut default_count = 0;
+    for imp in impls.into_iter().filter(|i| i.trait_name == trait_name) {
+        if imp.is_default {
+            default_count += 1;
+        } else {
+            concrete.push(imp.expr);
+        }
+    }
+    (concrete, default_count)
+}
+
+/// Computes every coverage problem across a crate's `#[os_impl(...)]` and
+/// `#[enforce_os_support(...)]` declarations: unverifiable coverage, missing
+/// impls, conflicting impls, duplicate defaults, and orphan impls. Pulled out
+/// of `verify_os_coverage` so it can be unit-tested directly against
+/// hand-built `ImplRecord`/`RequireRecord` values instead of real source files.
+fn compute_problems(impls: &[ImplRecord], requirements: &[RequireRecord]) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    // Unverifiable: an os_impl for an enforced trait whose cfg isn't a pure
+    // target_os disjunction. We can't tell which OSes it covers, so rather
+    // than silently ignore it (and risk a false "missing" below) or silently
+    // count it as covering everything (the bug this replaces), flag it.
+    let required_traits: HashSet<&str> = requirements.iter().map(|r| r.trait_name.as_str()).collect();
+    for imp in impls {
+        if !imp.is_default && imp.os_disjunction.is_none() && required_traits.contains(imp.trait_name.as_str()) {
+            problems.push(format!(
+                "unverifiable coverage: trait `{}`'s os_impl at {}:{} uses `{}`, which isn't a pure target_os disjunction (no not()/all()/non-target_os predicates); its OS coverage cannot be statically verified",
+                imp.trait_name, imp.file, imp.line, imp.expr
+            ));
+        }
+    }
+
+    // Missing: a required (trait, OS) pair with no covering impl. A registered
+    // default/fallback covers every required OS for its trait.
+    for req in requirements {
+        let has_default = impls.iter().any(|i| i.trait_name == req.trait_name && i.is_default);
+        if has_default {
+            continue;
+        }
+        for os in &req.required_oses {
+            let covered = impls.iter().any(|i| {
+                i.trait_name == req.trait_name
+                    && i.os_disjunction.as_ref().is_some_and(|oses| oses.iter().any(|o| o == os))
+            });
+            if !covered {
+                problems.push(format!(
+                    "missing impl: trait `{}` has no os_impl covering OS `{}` (required at {}:{})",
+                    req.trait_name, os, req.file, req.line
+                ));
+            }
+        }
+    }
+
+    // Conflicting: two concrete impls for the same trait both claiming the
+    // same OS. Defaults never conflict by construction (their cfg is the
+    // negation of every concrete sibling); impls with an unverifiable cfg
+    // are excluded too (already flagged above, and we have no sound OS list
+    // to compare them with).
+    for i in 0..impls.len() {
+        for j in (i + 1)..impls.len() {
+            let (a, b) = (&impls[i], &impls[j]);
+            if a.trait_name != b.trait_name {
+                continue;
+            }
+            let (Some(a_oses), Some(b_oses)) = (&a.os_disjunction, &b.os_disjunction) else {
+                continue;
+            };
+            for os in a_oses.iter().filter(|os| b_oses.contains(os)) {
+                problems.push(format!(
+                    "conflicting impls: trait `{}` OS `{}` is covered by both {}:{} and {}:{}",
+                    a.trait_name, os, a.file, a.line, b.file, b.line
+                ));
+            }
+        }
+    }
+
+    // Duplicate default: more than one #[os_impl(default)] for the same trait.
+    let mut seen_default = HashSet::new();
+    for imp in impls.iter().filter(|i| i.is_default) {
+        if !seen_default.insert(imp.trait_name.as_str()) {
+            problems.push(format!(
+                "duplicate default: trait `{}` has more than one #[os_impl(default)] (see {}:{})",
+                imp.trait_name, imp.file, imp.line
+            ));
+        }
+    }
+
+    // Orphan: an os_impl for a trait that no enforce_os_support requires.
+    for imp in impls {
+        if !required_traits.contains(imp.trait_name.as_str()) {
+            problems.push(format!(
+                "orphan impl: trait `{}` is implemented at {}:{} but no enforce_os_support requires it",
+                imp.trait_name, imp.file, imp.line
+            ));
+        }
+    }
+
+    problems
+}
+
+/// Implementation behind the crate-root `verify_os_coverage!()` macro (proc-macro
+/// entry points must live at the crate root, so `lib.rs` just forwards here).
+///
+/// Walks the crate's `src` tree, collects every `#[os_impl(...)]` and
+/// `#[enforce_os_support(...)]` declaration, and emits a `#[test]` that
+/// fails under `cargo test` when it finds a required OS with no impl, two impls
+/// claiming the same `(trait, OS)` pair, or an `os_impl` for a trait nobody
+/// enforces.
+///
+/// Also emits `OS_COVERAGE_SUMMARY` (a `trait -> { os -> file:line }` table
+/// rendered as a plain string) and a runtime introspection API —
+/// `supported_oses`, `is_supported`, `current_os_supports` — derived from
+/// the same scan, so callers can ask at runtime which traits are backed on
+/// the platform they're actually running on.
+pub(crate) fn verify_os_coverage(_input: TokenStream) -> TokenStream {
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR")
+        .expect("verify_os_coverage!() must be invoked while building a crate (CARGO_MANIFEST_DIR unset)");
+    let manifest_dir = PathBuf::from(manifest_dir);
+    let src_dir = manifest_dir.join("src");
+
+    let mut impls = Vec::new();
+    let mut requirements = Vec::new();
+    walk_dir(&src_dir, &mut impls, &mut requirements, &manifest_dir);
+
+    let problems = compute_problems(&impls, &requirements);
+
+    // Structured coverage summary: trait -> { os -> file:line }. A default
+    // is recorded under the synthetic "<default>" key.
+    let mut by_trait: BTreeMap<&str, BTreeMap<&str, String>> = BTreeMap::new();
+    for imp in &impls {
+        if imp.is_default {
+            by_trait
+                .entry(imp.trait_name.as_str())
+                .or_default()
+                .entry("<default>")
+                .or_insert_with(|| format!("{}:{}", imp.file, imp.line));
+            continue;
+        }
+        // `None` means the cfg isn't a pure target_os disjunction, which
+        // `compute_problems` flags as unverifiable when the trait is
+        // enforced (or as an orphan when it isn't); it contributes no OS to the
+        // summary rather than guessing one from a `not()`/`all()`/bare name.
+        let Some(oses) = &imp.os_disjunction else {
+            continue;
+        };
+        for os in oses {
+            by_trait
+                .entry(imp.trait_name.as_str())
+                .or_default()
+                .entry(os.as_str())
+                .or_insert_with(|| format!("{}:{}", imp.file, imp.line));
+        }
+    }
+    let mut summary = String::new();
+    for (trait_name, oses) in &by_trait {
+        summary.push_str(trait_name);
+        summary.push('\n');
+        for (os, loc) in oses {
+            summary.push_str(&format!("  {} -> {}\n", os, loc));
+        }
+    }
+    let summary_lit = LitStr::new(&summary, Span::call_site());
+
+    let test_body = if problems.is_empty() {
+        quote! {}
+    } else {
+        let message_lit = LitStr::new(&problems.join("\n"), Span::call_site());
+        quote! { panic!("{}", #message_lit); }
+    };
+
+    // Runtime introspection: `supported_oses`/`is_supported`/`current_os_supports`
+    // over the same per-trait OS lists the coverage summary is built from, so
+    // callers don't hand-maintain a list the macros already derived. Traits
+    // with a registered default/fallback are supported on every OS, since
+    // the default covers whatever no concrete impl claims. `by_trait` only
+    // ever contains `target_os` values that survived `os_disjunction`'s sound
+    // reduction, so a `not()`-excluded OS or a bare predicate name (`unix`,
+    // which `current_os_supports` could never match anyway since
+    // `std::env::consts::OS` is never `"unix"`) can never show up here.
+    let supported_oses_arms = by_trait.iter().map(|(trait_name, oses)| {
+        let trait_lit = LitStr::new(trait_name, Span::call_site());
+        let os_lits: Vec<_> = oses
+            .keys()
+            .filter(|os| **os != "<default>")
+            .map(|os| LitStr::new(os, Span::call_site()))
+            .collect();
+        quote! { #trait_lit => &[#(#os_lits),*], }
+    });
+    let default_trait_lits: Vec<_> = by_trait
+        .iter()
+        .filter(|(_, oses)| oses.contains_key("<default>"))
+        .map(|(trait_name, _)| LitStr::new(trait_name, Span::call_site()))
+        .collect();
+
+    let output = quote! {
+        #[doc(hidden)]
+        pub const OS_COVERAGE_SUMMARY: &str = #summary_lit;
+
+        #[doc(hidden)]
+        const TRAITS_WITH_DEFAULT: &[&str] = &[#(#default_trait_lits),*];
+
+        /// Every OS the given trait (by name) is known to have an `os_impl`
+        /// soundly covering — an OS a `not()`/`all()` or other impure cfg
+        /// merely *mentions* without actually being implemented for doesn't
+        /// count. Does not reflect a registered default/fallback; see
+        /// [`is_supported`].
+        pub fn supported_oses(trait_name: &str) -> &'static [&'static str] {
+            match trait_name {
+                #(#supported_oses_arms)*
+                _ => &[],
+            }
+        }
+
+        /// Whether `trait_name` has an `os_impl` covering `os`, or has a
+        /// registered default/fallback (which covers every OS).
+        pub fn is_supported(trait_name: &str, os: &str) -> bool {
+            TRAITS_WITH_DEFAULT.contains(&trait_name) || supported_oses(trait_name).contains(&os)
+        }
+
+        /// Whether `trait_name` has an `os_impl` covering the OS this binary
+        /// is currently running on.
+        pub fn current_os_supports(trait_name: &str) -> bool {
+            is_supported(trait_name, std::env::consts::OS)
+        }
+
+        #[test]
+        fn verify_os_coverage() {
+            #test_body
+        }
+    };
+    output.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg_expr::Cfg;
+
+    fn os_expr(oses: &[&str]) -> CfgExpr {
+        CfgExpr::Any(
+            oses.iter()
+                .map(|os| CfgExpr::Value(Cfg::KeyPair("target_os".to_string(), os.to_string())))
+                .collect(),
+        )
+    }
+
+    fn concrete_impl(trait_name: &str, expr: CfgExpr, file: &str, line: usize) -> ImplRecord {
+        ImplRecord {
+            trait_name: trait_name.to_string(),
+            os_disjunction: expr.target_os_disjunction(),
+            expr,
+            is_default: false,
+            file: file.to_string(),
+            line,
+        }
+    }
+
+    fn default_impl(trait_name: &str, file: &str, line: usize) -> ImplRecord {
+        ImplRecord {
+            trait_name: trait_name.to_string(),
+            os_disjunction: None,
+            expr: CfgExpr::Any(Vec::new()),
+            is_default: true,
+            file: file.to_string(),
+            line,
+        }
+    }
+
+    fn requirement(trait_name: &str, required_oses: &[&str], file: &str, line: usize) -> RequireRecord {
+        RequireRecord {
+            trait_name: trait_name.to_string(),
+            required_oses: required_oses.iter().map(|s| s.to_string()).collect(),
+            file: file.to_string(),
+            line,
+        }
+    }
+
+    #[test]
+    fn reports_missing_when_no_impl_covers_a_required_os() {
+        let impls = vec![concrete_impl("Foo", os_expr(&["linux"]), "a.rs", 1)];
+        let reqs = vec![requirement("Foo", &["linux", "macos"], "a.rs", 10)];
+        let problems = compute_problems(&impls, &reqs);
+        assert!(problems.iter().any(|p| p.contains("missing impl") && p.contains("macos")));
+        assert!(!problems.iter().any(|p| p.contains("missing impl") && p.contains("`linux`")));
+    }
+
+    #[test]
+    fn a_default_covers_every_required_os() {
+        let impls = vec![default_impl("Foo", "a.rs", 1)];
+        let reqs = vec![requirement("Foo", &["linux", "macos"], "a.rs", 10)];
+        let problems = compute_problems(&impls, &reqs);
+        assert!(problems.is_empty());
+    }
+
+    #[test]
+    fn not_and_all_exclusion_is_flagged_unverifiable_and_still_counts_as_missing() {
+        // `all(unix, not(target_os = "macos"))` excludes macOS; it must not
+        // be silently treated as covering the `macos` requirement.
+        let expr = CfgExpr::All(vec![
+            CfgExpr::Value(Cfg::Name("unix".to_string())),
+            CfgExpr::Not(Box::new(CfgExpr::Value(Cfg::KeyPair(
+                "target_os".to_string(),
+                "macos".to_string(),
+            )))),
+        ]);
+        let impls = vec![concrete_impl("Foo", expr, "a.rs", 1)];
+        let reqs = vec![requirement("Foo", &["macos"], "a.rs", 10)];
+        let problems = compute_problems(&impls, &reqs);
+        assert!(problems.iter().any(|p| p.contains("unverifiable coverage")));
+        assert!(problems.iter().any(|p| p.contains("missing impl") && p.contains("macos")));
+    }
+
+    #[test]
+    fn reports_conflicting_impls_for_the_same_trait_and_os() {
+        let impls = vec![
+            concrete_impl("Foo", os_expr(&["linux"]), "a.rs", 1),
+            concrete_impl("Foo", os_expr(&["linux", "macos"]), "b.rs", 2),
+        ];
+        let reqs = vec![requirement("Foo", &["linux", "macos"], "a.rs", 10)];
+        let problems = compute_problems(&impls, &reqs);
+        assert!(problems.iter().any(|p| p.contains("conflicting impls") && p.contains("linux")));
+        assert!(!problems.iter().any(|p| p.contains("conflicting impls") && p.contains("`macos`")));
+    }
+
+    #[test]
+    fn reports_duplicate_default() {
+        let impls = vec![default_impl("Foo", "a.rs", 1), default_impl("Foo", "b.rs", 2)];
+        let reqs = vec![requirement("Foo", &["linux"], "a.rs", 10)];
+        let problems = compute_problems(&impls, &reqs);
+        assert!(problems.iter().any(|p| p.contains("duplicate default")));
+    }
+
+    #[test]
+    fn reports_orphan_impl_for_an_unrequired_trait() {
+        let impls = vec![concrete_impl("Unused", os_expr(&["linux"]), "a.rs", 1)];
+        let problems = compute_problems(&impls, &[]);
+        assert!(problems.iter().any(|p| p.contains("orphan impl") && p.contains("Unused")));
+    }
+
+    #[test]
+    fn find_line_reports_each_occurrences_real_line_in_order() {
+        let source = "a\n#[os_impl(\"linux\")]\nb\n\nc\n#[os_impl(\"macos\")]\nd\n";
+        let mut cursor = 0;
+        assert_eq!(find_line(source, &mut cursor, "#[os_impl"), 2);
+        assert_eq!(find_line(source, &mut cursor, "#[os_impl"), 6);
+    }
+
+    #[test]
+    fn walk_dir_reports_each_impls_own_declaration_line_not_a_shared_one() {
+        // Regression test: this is the exact scenario the bug surfaced as —
+        // two os_impl blocks on different lines both reporting the line of
+        // whatever call site a real proc-macro Span::start() degenerates to.
+        let dir = std::env::temp_dir().join(format!(
+            "xostrait_verify_line_test_{}_{}",
+            std::process::id(),
+            line!()
+        ));
+        fs::create_dir_all(&dir).expect("create temp dir");
+        let source = concat!(
+            "pub trait Greeter {}\n",
+            "pub struct S;\n",
+            "\n",
+            "#[os_impl(\"linux\")]\n",
+            "impl Greeter for S {}\n",
+            "\n",
+            "#[os_impl(\"macos\")]\n",
+            "impl Greeter for S {}\n",
+        );
+        fs::write(dir.join("lib.rs"), source).expect("write temp source");
+
+        let mut impls = Vec::new();
+        let mut requirements = Vec::new();
+        walk_dir(&dir, &mut impls, &mut requirements, &dir);
+        fs::remove_dir_all(&dir).ok();
+
+        let lines: Vec<usize> = impls.iter().map(|i| i.line).collect();
+        assert_eq!(lines, vec![4, 7]);
+    }
+}